@@ -6,13 +6,19 @@
 // For a copy, see the LICENSE file.
 //
 
-pub use self::platform::{is_tty, AsRawObject, OsStrExt, Pipe, RawObject, RawObjectWrapper};
+pub use self::platform::{
+    is_tty, AsRawObject, OsStrExt, OwnedRawObject, Pipe, RawObject, RawObjectWrapper,
+};
 use super::{LockableRead, LockableWrite, MesaError, Result};
 
 use failure;
+#[cfg(target_os = "linux")]
+use libc;
 use std::borrow::Cow;
 use std::error::Error as StdError;
+use std::fs;
 use std::io::{self, Read, Write};
+use std::mem::MaybeUninit;
 use std::path::Path;
 use std::result::Result as StdResult;
 use std::str::FromStr;
@@ -42,6 +48,7 @@ impl Read for ReadableVec<u8> {
 pub struct UtilReadDyn {
     pub(crate) inner: Box<for<'a> LockableRead<'a>>,
     fd: Option<RawObject>,
+    owned_fd: Option<OwnedRawObject>,
 }
 
 impl UtilReadDyn {
@@ -49,12 +56,195 @@ impl UtilReadDyn {
         Self {
             inner: inner,
             fd: fd,
+            owned_fd: None,
+        }
+    }
+
+    /// Like [new](#method.new), but hands ownership of `fd` to the returned `UtilReadDyn`, which
+    /// closes it on drop. Useful when a util redirects a pipe/file into a sub-command and would
+    /// otherwise have to track and close the descriptor itself.
+    ///
+    /// # Safety
+    /// `inner` must not independently own (and therefore close on its own drop) the same
+    /// descriptor as `fd` — it must be a non-owning view over it, such as a
+    /// [RawObjectWrapper](platform/struct.RawObjectWrapper.html) built around a borrowed
+    /// reference, rather than e.g. a `File` constructed from `fd`. Otherwise the descriptor is
+    /// closed twice.
+    pub unsafe fn with_owned_fd(inner: Box<for<'a> LockableRead<'a>>, fd: OwnedRawObject) -> Self {
+        Self {
+            inner: inner,
+            fd: Some(fd.as_raw_object()),
+            owned_fd: Some(fd),
         }
     }
 
     pub fn fd(&self) -> Option<RawObject> {
         self.fd
     }
+
+    /// Read into the unfilled portion of `buf`, advancing its `filled` cursor by the number of
+    /// bytes read.
+    ///
+    /// The default implementation just zero-initializes whatever part of `buf` isn't already
+    /// initialized and delegates to [read](#method.read); utilities that read large amounts of
+    /// data in a loop can reuse the same [ReadBuf](struct.ReadBuf.html) (via
+    /// [ReadBuf::clear](struct.ReadBuf.html#method.clear)) across calls to skip the repeated
+    /// zeroing.
+    pub fn read_buf(&mut self, buf: &mut ReadBuf<'_>) -> io::Result<()> {
+        let n = self.read(buf.initialize_unfilled())?;
+        buf.advance(n);
+        Ok(())
+    }
+}
+
+/// A borrowed, possibly-uninitialized read buffer, modeled on the standard library's unstable
+/// `BorrowedBuf`/`ReadBuf` types.
+///
+/// `buf[0..initialized]` is always valid to read (every byte there has been written at least
+/// once, though not necessarily meaningfully), `filled <= initialized`, and only `buf[0..filled]`
+/// is ever exposed to callers as `&[u8]`.  This lets a reader reuse one large backing buffer
+/// across many reads without re-zeroing it each time.
+pub struct ReadBuf<'a> {
+    buf: &'a mut [MaybeUninit<u8>],
+    filled: usize,
+    initialized: usize,
+}
+
+impl<'a> ReadBuf<'a> {
+    /// Wrap an entirely uninitialized buffer.
+    pub fn uninit(buf: &'a mut [MaybeUninit<u8>]) -> Self {
+        Self {
+            buf,
+            filled: 0,
+            initialized: 0,
+        }
+    }
+
+    /// Wrap an already-initialized buffer.
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        let initialized = buf.len();
+        // safe: `u8` and `MaybeUninit<u8>` share a layout, and `initialized` is set to the
+        // whole buffer below so nothing is ever read as initialized that wasn't
+        let buf = unsafe { &mut *(buf as *mut [u8] as *mut [MaybeUninit<u8>]) };
+        Self {
+            buf,
+            filled: 0,
+            initialized,
+        }
+    }
+
+    /// Total capacity of the backing buffer.
+    pub fn capacity(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// The portion of the buffer that has actually been written to.
+    pub fn filled(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.buf.as_ptr() as *const u8, self.filled) }
+    }
+
+    pub fn filled_len(&self) -> usize {
+        self.filled
+    }
+
+    pub fn initialized_len(&self) -> usize {
+        self.initialized
+    }
+
+    /// Mark `n` additional bytes, immediately following the already-filled region, as
+    /// initialized. The caller must guarantee those bytes were actually written.
+    ///
+    /// # Safety
+    /// The caller must have written to `buf[filled..filled + n]` before calling this.
+    pub unsafe fn assume_init(&mut self, n: usize) {
+        self.initialized = self.initialized.max(self.filled + n);
+    }
+
+    /// Mark the next `n` bytes of the initialized-but-unfilled region as filled.
+    ///
+    /// Panics if that would advance `filled` past `initialized`.
+    pub fn advance(&mut self, n: usize) {
+        assert!(self.filled + n <= self.initialized);
+        self.filled += n;
+    }
+
+    /// Reset `filled` back to zero without touching `initialized`, so a previously-zeroed
+    /// buffer can be reused for another read without paying to zero it again.
+    pub fn clear(&mut self) {
+        self.filled = 0;
+    }
+
+    /// Zero-initialize any not-yet-initialized tail of the buffer and return the whole unfilled
+    /// region (`filled..capacity`) as a plain `&mut [u8]`.
+    pub fn initialize_unfilled(&mut self) -> &mut [u8] {
+        if self.initialized < self.buf.len() {
+            for slot in &mut self.buf[self.initialized..] {
+                *slot = MaybeUninit::new(0);
+            }
+            self.initialized = self.buf.len();
+        }
+
+        let filled = self.filled;
+        unsafe {
+            std::slice::from_raw_parts_mut(
+                self.buf[filled..].as_mut_ptr() as *mut u8,
+                self.buf.len() - filled,
+            )
+        }
+    }
+}
+
+#[test]
+fn read_buf_initialize_unfilled_zeroes_and_advance_fills() {
+    let mut backing = [MaybeUninit::<u8>::uninit(); 8];
+    let mut buf = ReadBuf::uninit(&mut backing);
+
+    assert_eq!(buf.capacity(), 8);
+    assert_eq!(buf.filled(), &[] as &[u8]);
+
+    let unfilled = buf.initialize_unfilled();
+    assert_eq!(unfilled, &[0u8; 8]);
+    unfilled[..3].copy_from_slice(b"abc");
+    buf.advance(3);
+
+    assert_eq!(buf.filled(), b"abc");
+    assert_eq!(buf.filled_len(), 3);
+    assert_eq!(buf.initialized_len(), 8);
+}
+
+#[test]
+fn read_buf_clear_reuses_initialized_region_without_rezeroing() {
+    let mut backing = [MaybeUninit::<u8>::uninit(); 4];
+    let mut buf = ReadBuf::uninit(&mut backing);
+
+    buf.initialize_unfilled().copy_from_slice(b"data");
+    buf.advance(4);
+    assert_eq!(buf.filled(), b"data");
+
+    buf.clear();
+    assert_eq!(buf.filled(), &[] as &[u8]);
+    // already initialized from the first round, so this must not zero it out again
+    assert_eq!(buf.initialize_unfilled(), b"data");
+    assert_eq!(buf.initialized_len(), 4);
+}
+
+#[test]
+#[should_panic]
+fn read_buf_advance_panics_past_initialized() {
+    let mut backing = [MaybeUninit::<u8>::uninit(); 4];
+    let mut buf = ReadBuf::uninit(&mut backing);
+    buf.advance(1);
+}
+
+#[test]
+fn read_buf_new_wraps_already_initialized_buffer() {
+    let mut backing = *b"hello";
+    let mut buf = ReadBuf::new(&mut backing);
+
+    assert_eq!(buf.initialized_len(), 5);
+    assert_eq!(buf.filled(), &[] as &[u8]);
+    buf.advance(5);
+    assert_eq!(buf.filled(), b"hello");
 }
 
 /// A structure to enable using dynamic dispatch with an object that implements
@@ -62,6 +252,7 @@ impl UtilReadDyn {
 pub struct UtilWriteDyn {
     pub(crate) inner: Box<for<'a> LockableWrite<'a>>,
     fd: Option<RawObject>,
+    owned_fd: Option<OwnedRawObject>,
 }
 
 impl UtilWriteDyn {
@@ -69,6 +260,25 @@ impl UtilWriteDyn {
         Self {
             inner: inner,
             fd: fd,
+            owned_fd: None,
+        }
+    }
+
+    /// Like [new](#method.new), but hands ownership of `fd` to the returned `UtilWriteDyn`,
+    /// which closes it on drop. Useful when a util redirects a pipe/file into a sub-command and
+    /// would otherwise have to track and close the descriptor itself.
+    ///
+    /// # Safety
+    /// `inner` must not independently own (and therefore close on its own drop) the same
+    /// descriptor as `fd` — it must be a non-owning view over it, such as a
+    /// [RawObjectWrapper](platform/struct.RawObjectWrapper.html) built around a borrowed
+    /// reference, rather than e.g. a `File` constructed from `fd`. Otherwise the descriptor is
+    /// closed twice.
+    pub unsafe fn with_owned_fd(inner: Box<for<'a> LockableWrite<'a>>, fd: OwnedRawObject) -> Self {
+        Self {
+            inner: inner,
+            fd: Some(fd.as_raw_object()),
+            owned_fd: Some(fd),
         }
     }
 
@@ -93,6 +303,273 @@ impl Write for UtilWriteDyn {
     }
 }
 
+/// Copy up to `len` bytes (or until EOF if `len` is `None`) from `reader` to `writer`, returning
+/// the number of bytes actually copied.
+///
+/// When both sides expose a [RawObject](platform/type.RawObject.html) (see
+/// [AsRawObject](platform/trait.AsRawObject.html)) this tries to avoid bouncing the data through
+/// a userspace buffer.  On Linux that means `copy_file_range(2)`, falling back to `sendfile(2)`
+/// on `EXDEV`/`ENOSYS`/`EINVAL`, and falling back further to `splice(2)` through an intermediate
+/// pipe for endpoints (such as pipes or sockets) that `sendfile(2)` can't handle.  Any failure of
+/// the fast path, or running on a non-Linux platform, or either side lacking a usable fd, falls
+/// back to a plain read/write loop over `reader`/`writer`.
+pub fn copy(reader: &mut UtilReadDyn, writer: &mut UtilWriteDyn, len: Option<u64>) -> Result<u64> {
+    let mut done = 0u64;
+
+    #[cfg(target_os = "linux")]
+    {
+        if let (Some(in_fd), Some(out_fd)) = (reader.fd(), writer.fd()) {
+            let remaining = len.map(|len| len.saturating_sub(done));
+            match linux_copy::copy_fast(in_fd, out_fd, remaining) {
+                Ok(n) => {
+                    done += n;
+                    if len.map(|len| done >= len).unwrap_or(false) {
+                        return Ok(done);
+                    }
+                    // the fast path hit EOF (or ran out of budget); nothing more to do
+                    if remaining.map(|r| n < r).unwrap_or(true) {
+                        return Ok(done);
+                    }
+                }
+                Err((partial, _e)) => {
+                    // fall through to the generic loop below, continuing from wherever the
+                    // fast path left the underlying fds' offsets; `partial` bytes already made
+                    // it to `writer` before the failure, so they must count towards `done` and
+                    // must not be copied again
+                    done += partial;
+                }
+            }
+        }
+    }
+
+    let remaining = len.map(|len| len.saturating_sub(done));
+    done += copy_generic(reader, writer, remaining)?;
+    Ok(done)
+}
+
+/// Generic, portable fallback for [copy](fn.copy.html): a plain read/write loop that works for
+/// any `Read`/`Write` pair, not just ones backed by a [RawObject](platform/type.RawObject.html).
+fn copy_generic<R: Read + ?Sized, W: Write + ?Sized>(
+    reader: &mut R,
+    writer: &mut W,
+    len: Option<u64>,
+) -> Result<u64> {
+    let mut buf = [0u8; 64 * 1024];
+    let mut total = 0u64;
+    let mut remaining = len;
+
+    loop {
+        if remaining == Some(0) {
+            break;
+        }
+        let want = remaining
+            .map(|r| r.min(buf.len() as u64) as usize)
+            .unwrap_or_else(|| buf.len());
+        let n = reader.read(&mut buf[..want])?;
+        if n == 0 {
+            break;
+        }
+        writer.write_all(&buf[..n])?;
+        total += n as u64;
+        if let Some(r) = remaining.as_mut() {
+            *r -= n as u64;
+        }
+    }
+
+    Ok(total)
+}
+
+#[test]
+fn copy_generic_respects_len_and_returns_count() {
+    let data = b"the quick brown fox jumps over the lazy dog".to_vec();
+
+    let mut reader = &data[..];
+    let mut writer = Vec::new();
+    let n = copy_generic(&mut reader, &mut writer, None).unwrap();
+    assert_eq!(n, data.len() as u64);
+    assert_eq!(writer, data);
+
+    let mut reader = &data[..];
+    let mut writer = Vec::new();
+    let n = copy_generic(&mut reader, &mut writer, Some(9)).unwrap();
+    assert_eq!(n, 9);
+    assert_eq!(writer, b"the quick");
+}
+
+#[cfg(target_os = "linux")]
+mod linux_copy {
+    use super::platform::RawObject;
+    use std::io;
+    use std::ptr;
+
+    // matches the chunk size coreutils' cp uses for its copy_file_range loop
+    const CHUNK: usize = 1 << 20;
+
+    /// Copy up to `len` bytes (or until EOF if `len` is `None`) from `in_fd` to `out_fd` using
+    /// the fastest kernel-assisted mechanism available, returning the number of bytes copied.
+    ///
+    /// On failure, the error is paired with the number of bytes that had already reached
+    /// `out_fd` (and thus advanced both fds' offsets) before the failing mechanism gave up, so
+    /// that callers don't lose track of, or re-copy, those bytes when falling back further.
+    pub(super) fn copy_fast(
+        in_fd: RawObject,
+        out_fd: RawObject,
+        len: Option<u64>,
+    ) -> Result<u64, (u64, io::Error)> {
+        let mut done = 0u64;
+        let remaining = |done: u64| len.map(|len| len.saturating_sub(done));
+
+        match copy_file_range(in_fd, out_fd, remaining(done)) {
+            Ok(n) => return Ok(done + n),
+            Err((n, ref e))
+                if e.raw_os_error() == Some(libc::EXDEV)
+                    || e.raw_os_error() == Some(libc::ENOSYS)
+                    || e.raw_os_error() == Some(libc::EINVAL) =>
+            {
+                done += n;
+            }
+            Err((n, e)) => return Err((done + n, e)),
+        }
+
+        match sendfile(in_fd, out_fd, remaining(done)) {
+            Ok(n) => return Ok(done + n),
+            Err((n, ref e))
+                if e.raw_os_error() == Some(libc::EINVAL)
+                    || e.raw_os_error() == Some(libc::ENOSYS) =>
+            {
+                done += n;
+            }
+            Err((n, e)) => return Err((done + n, e)),
+        }
+
+        match splice_via_pipe(in_fd, out_fd, remaining(done)) {
+            Ok(n) => Ok(done + n),
+            Err((n, e)) => Err((done + n, e)),
+        }
+    }
+
+    fn copy_file_range(
+        in_fd: RawObject,
+        out_fd: RawObject,
+        len: Option<u64>,
+    ) -> Result<u64, (u64, io::Error)> {
+        let mut remaining = len.unwrap_or(u64::max_value());
+        let mut total = 0u64;
+        while remaining > 0 {
+            let chunk = remaining.min(CHUNK as u64) as usize;
+            let n = unsafe {
+                libc::copy_file_range(in_fd, ptr::null_mut(), out_fd, ptr::null_mut(), chunk, 0)
+            };
+            if n < 0 {
+                return Err((total, io::Error::last_os_error()));
+            } else if n == 0 {
+                break;
+            }
+            total += n as u64;
+            remaining -= n as u64;
+        }
+        Ok(total)
+    }
+
+    fn sendfile(
+        in_fd: RawObject,
+        out_fd: RawObject,
+        len: Option<u64>,
+    ) -> Result<u64, (u64, io::Error)> {
+        let mut remaining = len.unwrap_or(u64::max_value());
+        let mut total = 0u64;
+        while remaining > 0 {
+            let chunk = remaining.min(CHUNK as u64) as usize;
+            let n = unsafe { libc::sendfile(out_fd, in_fd, ptr::null_mut(), chunk) };
+            if n < 0 {
+                return Err((total, io::Error::last_os_error()));
+            } else if n == 0 {
+                break;
+            }
+            total += n as u64;
+            remaining -= n as u64;
+        }
+        Ok(total)
+    }
+
+    // Used when at least one side is a pipe or socket that sendfile(2) can't deal with directly:
+    // splice both directions through an intermediate, kernel-internal pipe.
+    fn splice_via_pipe(
+        in_fd: RawObject,
+        out_fd: RawObject,
+        len: Option<u64>,
+    ) -> Result<u64, (u64, io::Error)> {
+        use super::Pipe;
+
+        let pipe = Pipe::new().map_err(|e| (0, e))?;
+        let pipe_r = AsRawObjectPipe::read_fd(&pipe);
+        let pipe_w = AsRawObjectPipe::write_fd(&pipe);
+
+        let mut remaining = len.unwrap_or(u64::max_value());
+        let mut total = 0u64;
+        while remaining > 0 {
+            let chunk = remaining.min(CHUNK as u64) as usize;
+            let n = unsafe {
+                libc::splice(
+                    in_fd,
+                    ptr::null_mut(),
+                    pipe_w,
+                    ptr::null_mut(),
+                    chunk,
+                    libc::SPLICE_F_MOVE,
+                )
+            };
+            if n < 0 {
+                return Err((total, io::Error::last_os_error()));
+            } else if n == 0 {
+                break;
+            }
+
+            // bytes only count towards `total` once they've actually reached `out_fd`, so a
+            // failure partway through draining this chunk doesn't over-report what was copied
+            let mut to_drain = n as usize;
+            while to_drain > 0 {
+                let n2 = unsafe {
+                    libc::splice(
+                        pipe_r,
+                        ptr::null_mut(),
+                        out_fd,
+                        ptr::null_mut(),
+                        to_drain,
+                        libc::SPLICE_F_MOVE,
+                    )
+                };
+                if n2 < 0 {
+                    return Err((total, io::Error::last_os_error()));
+                }
+                to_drain -= n2 as usize;
+                total += n2 as u64;
+            }
+
+            remaining -= n as u64;
+        }
+        Ok(total)
+    }
+
+    // small helper so we don't need to pull AsRawObject into scope just for the pipe's two ends
+    trait AsRawObjectPipe {
+        fn read_fd(&self) -> RawObject;
+        fn write_fd(&self) -> RawObject;
+    }
+
+    impl AsRawObjectPipe for super::Pipe {
+        fn read_fd(&self) -> RawObject {
+            use std::os::unix::io::AsRawFd;
+            self.read.as_raw_fd()
+        }
+
+        fn write_fd(&self) -> RawObject {
+            use std::os::unix::io::AsRawFd;
+            self.write.as_raw_fd()
+        }
+    }
+}
+
 pub(crate) struct ExitCodeWrapper(pub ExitCode);
 
 impl From<()> for ExitCodeWrapper {
@@ -125,17 +602,170 @@ pub(crate) fn string_to_err<T>(error: StdResult<T, String>) -> Result<T> {
     error.map_err(|e| failure::err_msg(e).compat().into())
 }
 
-// XXX: the idea for this function is to limit file traversal to one filesystem
-#[allow(dead_code)]
-pub(crate) fn one_filesystem<T, U>(_start_dir: T, _func: U) -> Result<()>
+/// Walk the directory tree rooted at `start_dir`, invoking `func` with the path and metadata of
+/// every entry found, but never descending into a subdirectory that lives on a different device
+/// than `start_dir` (as used by `--one-file-system` in `rm`, `du`, `chmod`, etc).
+///
+/// Symlinks found inside the tree are never followed, so a symlink to a directory on another
+/// device is reported to `func` like any other entry but is not traversed into; a symlinked
+/// `start_dir` itself is resolved and its target is walked.  Traversal stops at the first error
+/// returned by `func` or encountered while reading the tree.
+pub(crate) fn one_filesystem<T, U>(start_dir: T, mut func: U) -> Result<()>
 where
     T: AsRef<Path>,
-    U: FnMut() -> Result<()>,
+    U: FnMut(&Path, &fs::Metadata) -> Result<()>,
 {
-    // TODO: should probably loop over specified directory or something and call the function
+    let start_dir = start_dir.as_ref();
+    // follow symlinks here: if `start_dir` itself is a symlink to a directory, that's the
+    // directory the walk below actually reads (`fs::read_dir` follows it too), so the root
+    // device must be the device of what the symlink points to, not of its containing directory
+    let root_meta = fs::metadata(start_dir)?;
+    let root_dev = device_id(start_dir, &root_meta)?;
+
+    walk_one_filesystem(start_dir, root_dev, &mut func)
+}
+
+fn walk_one_filesystem<U>(dir: &Path, root_dev: DeviceId, func: &mut U) -> Result<()>
+where
+    U: FnMut(&Path, &fs::Metadata) -> Result<()>,
+{
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let meta = fs::symlink_metadata(&path)?;
+
+        func(&path, &meta)?;
+
+        // symlinks are reported above but never traversed into, regardless of device
+        if meta.file_type().is_dir() {
+            if device_id(&path, &meta)? != root_dev {
+                continue;
+            }
+            walk_one_filesystem(&path, root_dev, func)?;
+        }
+    }
+
     Ok(())
 }
 
+#[cfg(unix)]
+type DeviceId = u64;
+
+#[cfg(unix)]
+fn device_id(_path: &Path, meta: &fs::Metadata) -> Result<DeviceId> {
+    use std::os::unix::fs::MetadataExt;
+
+    Ok(meta.dev())
+}
+
+// Windows has no direct equivalent of `st_dev`, so the device boundary is approximated using the
+// volume serial number reported for the open file handle.
+#[cfg(windows)]
+type DeviceId = u32;
+
+#[cfg(windows)]
+fn device_id(path: &Path, _meta: &fs::Metadata) -> Result<DeviceId> {
+    use std::mem;
+    use std::os::windows::io::AsRawHandle;
+    use winapi::um::fileapi::{GetFileInformationByHandle, BY_HANDLE_FILE_INFORMATION};
+
+    let file = fs::File::open(path)?;
+    let mut info: BY_HANDLE_FILE_INFORMATION = unsafe { mem::zeroed() };
+    let ok = unsafe { GetFileInformationByHandle(file.as_raw_handle() as _, &mut info) };
+    if ok == 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+
+    Ok(info.dwVolumeSerialNumber)
+}
+
+#[cfg(test)]
+fn one_filesystem_test_dir(name: &str) -> std::path::PathBuf {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    let dir = std::env::temp_dir().join(format!(
+        "mesabox-one-filesystem-test-{}-{}-{}",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed),
+        name
+    ));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn one_filesystem_visits_nested_entries() {
+    let root = one_filesystem_test_dir("nested");
+    fs::create_dir(root.join("sub")).unwrap();
+    fs::write(root.join("top.txt"), b"top").unwrap();
+    fs::write(root.join("sub").join("nested.txt"), b"nested").unwrap();
+
+    let mut seen = Vec::new();
+    one_filesystem(&root, |path, _meta| {
+        seen.push(path.to_path_buf());
+        Ok(())
+    })
+    .unwrap();
+
+    assert!(seen.contains(&root.join("top.txt")));
+    assert!(seen.contains(&root.join("sub")));
+    assert!(seen.contains(&root.join("sub").join("nested.txt")));
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+#[cfg(unix)]
+fn one_filesystem_does_not_traverse_into_symlinked_dirs() {
+    use std::os::unix::fs::symlink;
+
+    let root = one_filesystem_test_dir("symlink");
+    let real = one_filesystem_test_dir("symlink-target");
+    fs::write(real.join("inside.txt"), b"inside").unwrap();
+    symlink(&real, root.join("link")).unwrap();
+
+    let mut seen = Vec::new();
+    one_filesystem(&root, |path, _meta| {
+        seen.push(path.to_path_buf());
+        Ok(())
+    })
+    .unwrap();
+
+    assert!(seen.contains(&root.join("link")));
+    assert!(!seen.contains(&root.join("link").join("inside.txt")));
+
+    fs::remove_dir_all(&root).unwrap();
+    fs::remove_dir_all(&real).unwrap();
+}
+
+#[test]
+#[cfg(unix)]
+fn one_filesystem_follows_symlinked_start_dir_for_root_device() {
+    use std::os::unix::fs::symlink;
+
+    let real = one_filesystem_test_dir("start-target");
+    fs::write(real.join("inside.txt"), b"inside").unwrap();
+    let link = one_filesystem_test_dir("start-link-parent").join("link");
+    symlink(&real, &link).unwrap();
+
+    let mut seen = Vec::new();
+    one_filesystem(&link, |path, _meta| {
+        seen.push(path.to_path_buf());
+        Ok(())
+    })
+    .unwrap();
+
+    // the symlinked root is resolved, so the target's entries are reported under the link's
+    // path; link and target share a device here, so this does not tell which of the two
+    // `root_dev` was taken from
+    assert!(seen.contains(&link.join("inside.txt")));
+
+    fs::remove_dir_all(&real).unwrap();
+    fs::remove_dir_all(link.parent().unwrap()).unwrap();
+}
+
 /// Get the actual path of a file or directory assuming `current_dir` is the current working
 /// directory.  If `current_dir` is `None` or `path` is an absolute path, the returned path will
 /// be `path`.
@@ -160,6 +790,77 @@ pub fn parse_obsolete_num(s: &str) -> Option<usize> {
     parse_num_common(s, &OBSOLETE_SUFFIXES, true)
 }
 
+/// Format `value` as a human-readable size, using the same suffix letters as
+/// [parse_num_with_suffix](fn.parse_num_with_suffix.html) (_e.g._ `format_num_with_suffix(1536,
+/// true) == "1.5KiB"`).
+///
+/// When `iec` is `true`, suffixes are powers of 1024 rendered as `"KiB"`/`"MiB"`/etc; otherwise
+/// they're powers of 1000 rendered as `"K"`/`"M"`/etc. Uses one fractional digit; see
+/// [format_num_with_suffix_precision](fn.format_num_with_suffix_precision.html) to change that.
+///
+/// The output does not round-trip through `parse_num_with_suffix`: that parser accepts neither
+/// the `"iB"` ending nor a fractional mantissa, and reads a bare `"K"` as 1024 rather than 1000.
+pub fn format_num_with_suffix(value: usize, iec: bool) -> String {
+    format_num_with_suffix_precision(value, iec, 1)
+}
+
+/// Like [format_num_with_suffix](fn.format_num_with_suffix.html), but rounding to an explicit
+/// number of fractional digits. Trailing zeros of the rounded fraction are dropped (so the
+/// fraction is omitted entirely when the rounded result is a whole number), and values smaller than the first suffix's base are rendered as a bare integer
+/// (_e.g._ `0 -> "0"`).
+pub fn format_num_with_suffix_precision(value: usize, iec: bool, precision: usize) -> String {
+    let base: usize = if iec { 1024 } else { 1000 };
+
+    let mut divisor: usize = 1;
+    let mut power = 0;
+    for _ in SUFFIXES.iter() {
+        let next = match divisor.checked_mul(base) {
+            Some(next) => next,
+            None => break,
+        };
+        if value < next {
+            break;
+        }
+        divisor = next;
+        power += 1;
+    }
+
+    if power == 0 {
+        return value.to_string();
+    }
+
+    let mut scaled = value as f64 / divisor as f64;
+    let mut formatted = format!("{:.*}", precision, scaled);
+
+    // rounding to `precision` digits can push the mantissa up to (or past) `base`, e.g.
+    // format_num_with_suffix(1048575, true) naively rounds to "1024.0KiB" instead of "1MiB";
+    // bump to the next tier whenever that happens
+    while power < SUFFIXES.len() && formatted.parse::<f64>().unwrap_or(scaled) >= base as f64 {
+        power += 1;
+        divisor = match divisor.checked_mul(base) {
+            Some(next) => next,
+            None => break,
+        };
+        scaled = value as f64 / divisor as f64;
+        formatted = format!("{:.*}", precision, scaled);
+    }
+
+    // only trim trailing zeros out of the fractional part (if any); `precision == 0` has no
+    // decimal point at all, so the whole integer mantissa must be left alone
+    let formatted = if formatted.contains('.') {
+        formatted.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        &formatted
+    };
+
+    let suffix = SUFFIXES[power - 1];
+    if iec {
+        format!("{}{}iB", formatted, suffix)
+    } else {
+        format!("{}{}", formatted, suffix)
+    }
+}
+
 fn parse_num_common(s: &str, suffixes: &[char], obsolete: bool) -> Option<usize> {
     let mut chars = s.chars();
     let mut found_si = false;
@@ -285,3 +986,57 @@ fn pow_correct() {
     assert_eq!(pow(2, 16), Some(65536));
     assert_eq!(pow(256, 2), Some(65536));
 }
+
+#[test]
+fn format_num_small() {
+    assert_eq!(format_num_with_suffix(0, true), "0");
+    assert_eq!(format_num_with_suffix(0, false), "0");
+    assert_eq!(format_num_with_suffix(1023, true), "1023");
+    assert_eq!(format_num_with_suffix(999, false), "999");
+}
+
+#[test]
+fn format_num_fraction() {
+    assert_eq!(format_num_with_suffix(1536, true), "1.5KiB");
+    assert_eq!(format_num_with_suffix(1500, false), "1.5K");
+}
+
+#[test]
+fn format_num_integer_omits_fraction() {
+    assert_eq!(format_num_with_suffix(2048, true), "2KiB");
+    assert_eq!(format_num_with_suffix(2000, false), "2K");
+}
+
+#[test]
+fn format_num_exact_powers_render_as_one() {
+    for (i, suffix) in SUFFIXES.iter().enumerate() {
+        let exp = i as u32 + 1;
+        if let Some(value) = pow(1024, exp) {
+            assert_eq!(
+                format_num_with_suffix(value, true),
+                format!("1{}iB", suffix)
+            );
+        }
+        if let Some(value) = pow(1000, exp) {
+            assert_eq!(format_num_with_suffix(value, false), format!("1{}", suffix));
+        }
+    }
+}
+
+#[test]
+fn format_num_rounding_does_not_overflow_tier() {
+    // 1048575 / 1024 rounds to "1024.0" at one fractional digit, which must bump to the next
+    // tier rather than print "1024KiB"
+    assert_eq!(format_num_with_suffix(1048575, true), "1MiB");
+    // 999999 / 1000 rounds to "1000.0" for the same reason
+    assert_eq!(format_num_with_suffix(999999, false), "1M");
+}
+
+#[test]
+fn format_num_precision_zero_keeps_whole_mantissa() {
+    // with no decimal point to stop at, the trailing-zero trim must not eat into the integer
+    // part of the mantissa itself
+    assert_eq!(format_num_with_suffix_precision(20000, false, 0), "20K");
+    assert_eq!(format_num_with_suffix_precision(10240, true, 0), "10KiB");
+    assert_eq!(format_num_with_suffix_precision(1000, false, 0), "1K");
+}
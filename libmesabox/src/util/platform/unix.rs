@@ -0,0 +1,178 @@
+//
+// Copyright (c) 2018, The MesaLock Linux Project Contributors
+// All rights reserved.
+//
+// This work is licensed under the terms of the BSD 3-Clause License.
+// For a copy, see the LICENSE file.
+//
+
+use libc;
+use std::fs::File;
+use std::io;
+use std::mem;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+
+pub use std::os::unix::ffi::OsStrExt;
+
+/// The type used to represent a raw, platform-specific object (a file descriptor on Unix).
+pub type RawObject = RawFd;
+
+/// A trait for objects that can expose their underlying [RawObject](type.RawObject.html).
+pub trait AsRawObject {
+    fn as_raw_object(&self) -> RawObject;
+}
+
+impl<T: AsRawFd> AsRawObject for T {
+    fn as_raw_object(&self) -> RawObject {
+        self.as_raw_fd()
+    }
+}
+
+/// Returns whether `fd` refers to a terminal.
+pub fn is_tty(fd: RawObject) -> bool {
+    unsafe { libc::isatty(fd) == 1 }
+}
+
+/// A simple wrapper pairing some object with the raw descriptor it was created from, so that
+/// the descriptor remains available for things like `copy_file_range`/`sendfile` fast paths even
+/// when the object is stored behind a trait object.
+pub struct RawObjectWrapper<T> {
+    pub(crate) inner: T,
+    fd: RawObject,
+}
+
+impl<T> RawObjectWrapper<T> {
+    pub fn new(inner: T, fd: RawObject) -> Self {
+        Self { inner: inner, fd: fd }
+    }
+}
+
+impl<T> AsRawObject for RawObjectWrapper<T> {
+    fn as_raw_object(&self) -> RawObject {
+        self.fd
+    }
+}
+
+impl<T: io::Read> io::Read for RawObjectWrapper<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl<T: io::Write> io::Write for RawObjectWrapper<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// A unidirectional OS pipe, with `read` being the readable end and `write` being the writable
+/// end.
+pub struct Pipe {
+    pub read: File,
+    pub write: File,
+}
+
+impl Pipe {
+    pub fn new() -> io::Result<Self> {
+        let mut fds: [RawFd; 2] = [0; 2];
+        if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self {
+            read: unsafe { File::from_raw_fd(fds[0]) },
+            write: unsafe { File::from_raw_fd(fds[1]) },
+        })
+    }
+}
+
+impl AsRawObject for Pipe {
+    fn as_raw_object(&self) -> RawObject {
+        self.read.as_raw_fd()
+    }
+}
+
+/// An owned [RawObject](type.RawObject.html) that closes its descriptor on drop, so callers
+/// don't have to manually track and close a redirected fd handed off to something like
+/// [UtilReadDyn](../struct.UtilReadDyn.html).
+pub struct OwnedRawObject(RawFd);
+
+impl OwnedRawObject {
+    /// Take ownership of `fd`. The caller must not close `fd` themselves afterwards.
+    pub unsafe fn from_raw(fd: RawObject) -> Self {
+        Self(fd)
+    }
+
+    /// Give up ownership of the descriptor, returning it without closing it.
+    pub fn into_raw(self) -> RawObject {
+        let fd = self.0;
+        mem::forget(self);
+        fd
+    }
+
+    /// Duplicate the descriptor with `dup(2)`, returning a new, independently-owned copy.
+    pub fn try_clone(&self) -> io::Result<Self> {
+        let fd = unsafe { libc::dup(self.0) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self(fd))
+    }
+}
+
+impl AsRawObject for OwnedRawObject {
+    fn as_raw_object(&self) -> RawObject {
+        self.0
+    }
+}
+
+impl Drop for OwnedRawObject {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.0);
+        }
+    }
+}
+
+#[test]
+fn owned_raw_object_try_clone_and_close_on_drop() {
+    let mut fds: [RawFd; 2] = [0; 2];
+    assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+    let (read_fd, write_fd) = (fds[0], fds[1]);
+
+    let owned = unsafe { OwnedRawObject::from_raw(write_fd) };
+    let cloned = owned.try_clone().unwrap();
+    let cloned_fd = cloned.as_raw_object();
+    assert_ne!(cloned_fd, write_fd);
+
+    drop(cloned);
+    // the dup is closed on its own drop...
+    assert_eq!(unsafe { libc::fcntl(cloned_fd, libc::F_GETFD) }, -1);
+    // ...independently of the original, which is still open
+    assert!(unsafe { libc::fcntl(owned.as_raw_object(), libc::F_GETFD) } >= 0);
+
+    drop(owned);
+    assert_eq!(unsafe { libc::fcntl(write_fd, libc::F_GETFD) }, -1);
+
+    unsafe { libc::close(read_fd) };
+}
+
+#[test]
+fn owned_raw_object_into_raw_does_not_close() {
+    let mut fds: [RawFd; 2] = [0; 2];
+    assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+    let (read_fd, write_fd) = (fds[0], fds[1]);
+
+    let owned = unsafe { OwnedRawObject::from_raw(write_fd) };
+    let raw = owned.into_raw();
+    assert_eq!(raw, write_fd);
+    assert!(unsafe { libc::fcntl(raw, libc::F_GETFD) } >= 0);
+
+    unsafe {
+        libc::close(raw);
+        libc::close(read_fd);
+    }
+}
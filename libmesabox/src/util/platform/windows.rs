@@ -0,0 +1,173 @@
+//
+// Copyright (c) 2018, The MesaLock Linux Project Contributors
+// All rights reserved.
+//
+// This work is licensed under the terms of the BSD 3-Clause License.
+// For a copy, see the LICENSE file.
+//
+
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io;
+use std::mem;
+use std::os::windows::io::{AsRawHandle, FromRawHandle, RawHandle};
+use winapi::um::handleapi::{CloseHandle, DuplicateHandle, DUPLICATE_SAME_ACCESS};
+use winapi::um::namedpipeapi::CreatePipe;
+use winapi::um::processthreadsapi::GetCurrentProcess;
+use winapi::um::winnt::HANDLE;
+
+/// The type used to represent a raw, platform-specific object (a `HANDLE` on Windows).
+pub type RawObject = RawHandle;
+
+/// A trait for objects that can expose their underlying [RawObject](type.RawObject.html).
+pub trait AsRawObject {
+    fn as_raw_object(&self) -> RawObject;
+}
+
+impl<T: AsRawHandle> AsRawObject for T {
+    fn as_raw_object(&self) -> RawObject {
+        self.as_raw_handle()
+    }
+}
+
+/// Windows does not expose `OsStr` as a plain byte sequence, so this trait stands in for the
+/// subset of `std::os::unix::ffi::OsStrExt` that the rest of the code relies on.
+pub trait OsStrExt {
+    fn as_bytes(&self) -> Vec<u8>;
+    fn from_bytes(slice: &[u8]) -> std::borrow::Cow<OsStr>;
+}
+
+impl OsStrExt for OsStr {
+    fn as_bytes(&self) -> Vec<u8> {
+        self.to_string_lossy().into_owned().into_bytes()
+    }
+
+    fn from_bytes(slice: &[u8]) -> std::borrow::Cow<OsStr> {
+        std::borrow::Cow::Owned(String::from_utf8_lossy(slice).into_owned().into())
+    }
+}
+
+/// Always returns `false`: console detection is not implemented on Windows, so no handle is
+/// reported as a console.
+pub fn is_tty(_object: RawObject) -> bool {
+    false
+}
+
+/// A simple wrapper pairing some object with the raw handle it was created from.
+pub struct RawObjectWrapper<T> {
+    pub(crate) inner: T,
+    handle: RawObject,
+}
+
+impl<T> RawObjectWrapper<T> {
+    pub fn new(inner: T, handle: RawObject) -> Self {
+        Self {
+            inner: inner,
+            handle: handle,
+        }
+    }
+}
+
+impl<T> AsRawObject for RawObjectWrapper<T> {
+    fn as_raw_object(&self) -> RawObject {
+        self.handle
+    }
+}
+
+impl<T: io::Read> io::Read for RawObjectWrapper<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl<T: io::Write> io::Write for RawObjectWrapper<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// A unidirectional OS pipe, with `read` being the readable end and `write` being the writable
+/// end.
+pub struct Pipe {
+    pub read: File,
+    pub write: File,
+}
+
+impl Pipe {
+    pub fn new() -> io::Result<Self> {
+        let mut read_handle: HANDLE = std::ptr::null_mut();
+        let mut write_handle: HANDLE = std::ptr::null_mut();
+        let res =
+            unsafe { CreatePipe(&mut read_handle, &mut write_handle, std::ptr::null_mut(), 0) };
+        if res == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self {
+            read: unsafe { File::from_raw_handle(read_handle as RawHandle) },
+            write: unsafe { File::from_raw_handle(write_handle as RawHandle) },
+        })
+    }
+}
+
+impl AsRawObject for Pipe {
+    fn as_raw_object(&self) -> RawObject {
+        self.read.as_raw_handle()
+    }
+}
+
+/// An owned [RawObject](type.RawObject.html) that closes its handle on drop, so callers don't
+/// have to manually track and close a redirected handle handed off to something like
+/// [UtilReadDyn](../struct.UtilReadDyn.html).
+pub struct OwnedRawObject(RawHandle);
+
+impl OwnedRawObject {
+    /// Take ownership of `handle`. The caller must not close `handle` themselves afterwards.
+    pub unsafe fn from_raw(handle: RawObject) -> Self {
+        Self(handle)
+    }
+
+    /// Give up ownership of the handle, returning it without closing it.
+    pub fn into_raw(self) -> RawObject {
+        let handle = self.0;
+        mem::forget(self);
+        handle
+    }
+
+    /// Duplicate the handle with `DuplicateHandle`, returning a new, independently-owned copy.
+    pub fn try_clone(&self) -> io::Result<Self> {
+        let mut dup: HANDLE = std::ptr::null_mut();
+        let ok = unsafe {
+            DuplicateHandle(
+                GetCurrentProcess(),
+                self.0 as HANDLE,
+                GetCurrentProcess(),
+                &mut dup,
+                0,
+                0,
+                DUPLICATE_SAME_ACCESS,
+            )
+        };
+        if ok == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self(dup as RawHandle))
+    }
+}
+
+impl AsRawObject for OwnedRawObject {
+    fn as_raw_object(&self) -> RawObject {
+        self.0
+    }
+}
+
+impl Drop for OwnedRawObject {
+    fn drop(&mut self) {
+        unsafe {
+            CloseHandle(self.0 as HANDLE);
+        }
+    }
+}